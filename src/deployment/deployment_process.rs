@@ -1,11 +1,16 @@
 use super::recipe::*;
 use super::tx_check::tx_check;
+// `Cell::hash_type`/`DepGroup::hash_type` (config.rs) and
+// `CellRecipe::hash_type`/`DepGroupRecipe::hash_type` (recipe.rs) are the
+// config/recipe-surface fields this file threads through `build_cell_recipe`/
+// `build_dep_group_recipe` below
 use crate::config::{Cell, CellLocation, DepGroup, Deployment};
 use crate::wallet::{cli_types::LiveCell, *};
 
 use anyhow::{anyhow, Result};
 use ckb_testtool::ckb_chain_spec::consensus::TYPE_ID_CODE_HASH;
 use ckb_testtool::ckb_hash::new_blake2b;
+use ckb_testtool::ckb_jsonrpc_types::{Script as JsonScript, Transaction as JsonTransaction};
 use ckb_testtool::ckb_types::{
     bytes::Bytes,
     core::{Capacity, ScriptHashType, TransactionBuilder, TransactionView},
@@ -14,24 +19,255 @@ use ckb_testtool::ckb_types::{
     H256,
 };
 use log::{debug, log_enabled, trace, Level::Debug};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::Read;
+use std::thread::sleep;
+use std::time::Duration;
 
-pub struct DeploymentProcess {
+// portable artifact carrying a recipe's unsigned txs plus each tx's
+// per-input lock scripts, for signing on an offline host
+#[derive(Serialize, Deserialize)]
+pub struct UnsignedDeploymentBundle {
+    pub recipe: DeploymentRecipe,
+    pub txs: Vec<JsonTransaction>,
+    pub input_locks: Vec<Vec<JsonScript>>,
+}
+
+// signed counterpart of `UnsignedDeploymentBundle`, ready for `execute_recipe`
+#[derive(Serialize, Deserialize)]
+pub struct SignedDeploymentBundle {
+    pub recipe: DeploymentRecipe,
+    pub txs: Vec<JsonTransaction>,
+}
+
+// lock script backing each input of a deployment tx, in input order. Every
+// input is a wallet-owned cell (pre-inputs are checked against the wallet's
+// lock by `check_pre_inputs_unlockable`, change and collected cells carry the
+// wallet's lock), so in practice all of a deployment tx's inputs share one
+// lock; `single_lock_group` enforces that rather than letting a `Signer`
+// silently sign as if it always held
+pub struct SigningContext {
+    pub input_locks: Vec<packed::Script>,
+}
+
+impl SigningContext {
+    // `signing_message`/`apply_signature` compute one sighash for the whole
+    // tx and write it into witness 0, which is only correct if every input
+    // shares a lock; this is the one place that invariant is checked
+    fn single_lock_group(&self) -> Result<&packed::Script> {
+        let first = self
+            .input_locks
+            .first()
+            .ok_or_else(|| anyhow!("tx has no inputs to sign"))?;
+        if self.input_locks.iter().all(|lock| lock == first) {
+            Ok(first)
+        } else {
+            Err(anyhow!(
+                "tx inputs span more than one lock script; only single-lock-group deployment txs are supported"
+            ))
+        }
+    }
+}
+
+// turns an unsigned deployment transaction into a signed one; `WalletSigner`
+// is the local keystore implementation, `LedgerSigner` a hardware one
+pub trait Signer {
+    fn sign(&self, tx: TransactionView, context: SigningContext) -> Result<TransactionView>;
+}
+
+// signs with the password-protected keystore bundled in `Wallet`, reading the
+// password afresh for each tx it signs
+pub struct WalletSigner {
+    wallet: Wallet,
+}
+
+impl WalletSigner {
+    pub fn new(wallet: Wallet) -> Self {
+        WalletSigner { wallet }
+    }
+}
+
+impl Signer for WalletSigner {
+    fn sign(&self, tx: TransactionView, context: SigningContext) -> Result<TransactionView> {
+        context.single_lock_group()?;
+        let password = self.wallet.read_password().expect("read password");
+        self.wallet.sign_tx(tx, password)
+    }
+}
+
+// transport to a connected Ledger-style hardware device; a concrete
+// implementation (USB/HID framing, APDU encoding, ...) lives outside this crate
+pub trait LedgerDevice {
+    // display the tx's outputs/capacity on-device for visual confirmation
+    fn display_for_confirmation(&self, tx: &TransactionView) -> Result<()>;
+    // ask the device to sign `message`, returning a recoverable signature
+    fn sign_message(&self, message: &[u8; 32]) -> Result<[u8; 65]>;
+}
+
+// signs by delegating to a connected Ledger-style hardware device
+pub struct LedgerSigner<D: LedgerDevice> {
+    device: D,
+}
+
+impl<D: LedgerDevice> LedgerSigner<D> {
+    pub fn new(device: D) -> Self {
+        LedgerSigner { device }
+    }
+}
+
+impl<D: LedgerDevice> Signer for LedgerSigner<D> {
+    fn sign(&self, tx: TransactionView, context: SigningContext) -> Result<TransactionView> {
+        context.single_lock_group()?;
+        self.device.display_for_confirmation(&tx)?;
+        let message = signing_message(&tx);
+        let signature = self.device.sign_message(&message)?;
+        Ok(apply_signature(tx, &context, signature))
+    }
+}
+
+// sighash_all-style message: blake2b over the tx hash followed by every
+// witness's length-prefixed bytes, in order; witness 0 is hashed as it stands
+// in the tx, its lock field is not zeroed here
+fn signing_message(tx: &TransactionView) -> [u8; 32] {
+    let mut blake2b = new_blake2b();
+    blake2b.update(tx.hash().as_slice());
+    if let Some(witness) = tx.witnesses().get(0) {
+        let witness_len = witness.raw_data().len() as u64;
+        blake2b.update(&witness_len.to_le_bytes());
+        blake2b.update(&witness.raw_data());
+    }
+    for witness in tx.witnesses().into_iter().skip(1) {
+        let witness_len = witness.raw_data().len() as u64;
+        blake2b.update(&witness_len.to_le_bytes());
+        blake2b.update(&witness.raw_data());
+    }
+    let mut message = [0u8; 32];
+    blake2b.finalize(&mut message);
+    message
+}
+
+// place the device-returned signature into the first witness's lock field,
+// matching the layout the default secp256k1 lock script expects
+fn apply_signature(
+    tx: TransactionView,
+    _context: &SigningContext,
+    signature: [u8; 65],
+) -> TransactionView {
+    let witness = tx
+        .witnesses()
+        .get(0)
+        .and_then(|w| packed::WitnessArgs::from_slice(&w.raw_data()).ok())
+        .unwrap_or_default()
+        .as_builder()
+        .lock(Some(Bytes::from(signature.to_vec())).pack())
+        .build();
+    let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+    witnesses[0] = witness.as_bytes().pack();
+    tx.as_advanced_builder().set_witnesses(witnesses).build()
+}
+
+// which deployment steps (keyed by cell/dep-group name) have already been
+// sent and confirmed, persisted so an interrupted `execute_recipe` can resume
+#[derive(Default, Serialize, Deserialize)]
+struct DeploymentProgress {
+    confirmed: HashMap<String, H256>,
+}
+
+impl DeploymentProgress {
+    fn load(path: &str) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// whether `tx_hash` for `name` was already confirmed in a previous,
+// interrupted run, per the persisted `progress`
+fn already_confirmed_from_previous_run(
+    progress: &DeploymentProgress,
+    name: &str,
+    tx_hash: &H256,
+) -> bool {
+    progress.confirmed.get(name) == Some(tx_hash)
+}
+
+// size budget in bytes for a single batched deployment tx; it is checked
+// against the serialized size of the batch's outputs and outputs data only,
+// so the inputs, cell deps and witnesses added afterwards are not counted
+pub const DEFAULT_MAX_TX_SIZE: usize = 200_000;
+
+pub struct DeploymentProcess<S: Signer = WalletSigner> {
     wallet: Wallet,
+    signer: S,
     tx_fee: Capacity,
+    max_tx_size: usize,
+    // how many blocks must be built on top of a tx's committing block before
+    // `execute_recipe` considers it confirmed and moves on to dependents
+    confirmation_depth: u64,
+    // where to persist which steps have already been confirmed, keyed by
+    // cell/dep-group name, so an interrupted `execute_recipe` can resume
+    progress_path: Option<String>,
     config: Deployment,
 }
 
-impl DeploymentProcess {
+impl DeploymentProcess<WalletSigner> {
+    // signs with `wallet`'s local password-protected keystore
     pub fn new(config: Deployment, wallet: Wallet, tx_fee: Capacity) -> Self {
+        let signer = WalletSigner::new(wallet.clone());
+        DeploymentProcess {
+            wallet,
+            signer,
+            tx_fee,
+            max_tx_size: DEFAULT_MAX_TX_SIZE,
+            confirmation_depth: 0,
+            progress_path: None,
+            config,
+        }
+    }
+}
+
+impl<S: Signer> DeploymentProcess<S> {
+    // signs with a custom `Signer` (e.g. a hardware wallet); `wallet` is still
+    // used for everything that isn't signing: building, querying, broadcasting
+    pub fn with_signer(config: Deployment, wallet: Wallet, signer: S, tx_fee: Capacity) -> Self {
         DeploymentProcess {
             wallet,
+            signer,
             tx_fee,
+            max_tx_size: DEFAULT_MAX_TX_SIZE,
+            confirmation_depth: 0,
+            progress_path: None,
             config,
         }
     }
 
+    pub fn with_max_tx_size(mut self, max_tx_size: usize) -> Self {
+        self.max_tx_size = max_tx_size;
+        self
+    }
+
+    // require `depth` blocks on top of a tx's committing block before
+    // `execute_recipe` treats it as confirmed
+    pub fn with_confirmation_depth(mut self, depth: u64) -> Self {
+        self.confirmation_depth = depth;
+        self
+    }
+
+    // persist confirmed deployment steps to `path`, so an interrupted
+    // `capsule deploy` can resume from the first unconfirmed step
+    pub fn with_progress_path(mut self, path: String) -> Self {
+        self.progress_path = Some(path);
+        self
+    }
+
     /// generate recipe and deploy
     pub fn prepare_recipe(
         &mut self,
@@ -47,6 +283,87 @@ impl DeploymentProcess {
         Ok((recipe, txs))
     }
 
+    // serialize an unsigned recipe/txs from `prepare_recipe` to `path`,
+    // together with each input's lock script, so it can be signed offline
+    // without the signing host needing to query a node for previous outputs
+    pub fn export_unsigned_recipe(
+        &self,
+        recipe: &DeploymentRecipe,
+        txs: &[TransactionView],
+        path: &str,
+    ) -> Result<()> {
+        let input_locks = txs
+            .iter()
+            .map(|tx| {
+                tx.inputs()
+                    .into_iter()
+                    .map(|input| -> JsonScript {
+                        self.wallet
+                            .get_cell_output(input.previous_output())
+                            .lock()
+                            .into()
+                    })
+                    .collect()
+            })
+            .collect();
+        let bundle = UnsignedDeploymentBundle {
+            recipe: recipe.to_owned(),
+            txs: txs.iter().map(|tx| tx.data().into()).collect(),
+            input_locks,
+        };
+        fs::write(path, serde_json::to_string_pretty(&bundle)?)?;
+        Ok(())
+    }
+
+    // load a bundle previously written by `export_unsigned_recipe`, including
+    // the per-tx input locks needed to sign without a live wallet/node
+    #[allow(clippy::type_complexity)]
+    pub fn import_unsigned_recipe(
+        path: &str,
+    ) -> Result<(
+        DeploymentRecipe,
+        Vec<TransactionView>,
+        Vec<Vec<packed::Script>>,
+    )> {
+        let bundle: UnsignedDeploymentBundle = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let txs = bundle
+            .txs
+            .into_iter()
+            .map(|tx| packed::Transaction::from(tx).into_view())
+            .collect();
+        let input_locks = bundle
+            .input_locks
+            .into_iter()
+            .map(|locks| locks.into_iter().map(packed::Script::from).collect())
+            .collect();
+        Ok((bundle.recipe, txs, input_locks))
+    }
+
+    // sign an exported bundle using only what travelled with it plus the
+    // local signer, with no wallet/node lookups for previous outputs, and
+    // write the signed recipe/txs to `out_path` for `execute_recipe_from_file`
+    pub fn sign_exported_recipe(&self, in_path: &str, out_path: &str) -> Result<()> {
+        let (recipe, txs, input_locks) = Self::import_unsigned_recipe(in_path)?;
+        let signed_txs = self.sign_txs_with_locks(txs, input_locks)?;
+        let bundle = SignedDeploymentBundle {
+            recipe,
+            txs: signed_txs.iter().map(|tx| tx.data().into()).collect(),
+        };
+        fs::write(out_path, serde_json::to_string_pretty(&bundle)?)?;
+        Ok(())
+    }
+
+    // load a signed bundle produced by `sign_exported_recipe` and broadcast it
+    pub fn execute_recipe_from_file(&mut self, path: &str) -> Result<()> {
+        let bundle: SignedDeploymentBundle = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let txs = bundle
+            .txs
+            .into_iter()
+            .map(|tx| packed::Transaction::from(tx).into_view())
+            .collect();
+        self.execute_recipe(bundle.recipe, txs)
+    }
+
     fn check_pre_inputs_unlockable(&self, pre_inputs_cell: &[(String, LiveCell)]) -> Result<()> {
         for (name, live_cell) in pre_inputs_cell {
             let cell_output: packed::CellOutput =
@@ -63,13 +380,14 @@ impl DeploymentProcess {
         Ok(())
     }
 
-    fn build_cell_tx(
+    // build one tx carrying every cell in `batch`, one output per cell
+    fn build_cell_batch_tx(
         &mut self,
-        cell: Cell,
-        data: Bytes,
+        batch: Vec<(Cell, Bytes)>,
+        pre_inputs_cells: &[(String, LiveCell)],
         input_cells: Vec<LiveCell>,
-    ) -> Result<TransactionView> {
-        trace!("build cell tx with inputs: {:?}", input_cells);
+    ) -> Result<(TransactionView, Vec<CellRecipe>)> {
+        trace!("build cell batch tx with inputs: {:?}", input_cells);
         let lock: packed::Script = self.config.lock.to_owned().into();
         let mut inputs_cells = Vec::new();
         for live_cell in input_cells {
@@ -77,8 +395,9 @@ impl DeploymentProcess {
                 .lock_out_points(vec![live_cell.out_point()].into_iter());
             inputs_cells.push(live_cell);
         }
-        // collect cells if inputs_cells is empty, type_id requires at least one input
-        if cell.enable_type_id && inputs_cells.is_empty() {
+        // a batch always needs at least one input, both to cover fees and so
+        // that any type-id output in the batch has an input to hash
+        if inputs_cells.is_empty() {
             inputs_cells.extend(
                 self.wallet
                     .collect_live_cells(Capacity::shannons(1))
@@ -88,39 +407,50 @@ impl DeploymentProcess {
             self.wallet
                 .lock_out_points(inputs_cells.iter().map(|c| c.out_point()));
         }
-        // build outputs
-        let output = {
+        let first_input = inputs_cells[0].input();
+
+        let mut outputs = Vec::with_capacity(batch.len());
+        let mut outputs_data = Vec::with_capacity(batch.len());
+        for (output_index, (cell, data)) in batch.iter().enumerate() {
             let mut output = packed::CellOutput::new_builder().lock(lock.clone());
             if cell.enable_type_id {
-                let input_cell = &inputs_cells[0];
-                let tx: packed::Transaction = self
-                    .wallet
-                    .query_transaction(&input_cell.tx_hash)?
-                    .expect("tx")
-                    .transaction
-                    .inner
-                    .into();
-                let tx: TransactionView = tx.into_view();
-                let input_cell_output =
-                    tx.outputs().get(input_cell.index as usize).expect("output");
-                // inherit type id from input cell or create a new one
-                let type_script = match input_cell_output.type_().to_opt() {
-                    Some(script) if is_type_id_script(&script) => script,
-                    _ => {
-                        let output_index = 0;
-                        build_type_id_script(&input_cell.input(), output_index)
+                let own_input = pre_inputs_cells
+                    .iter()
+                    .find(|(name, _input_cell)| name == &cell.name)
+                    .map(|(_name, input_cell)| input_cell);
+                let type_script = match own_input {
+                    Some(input_cell) => {
+                        let tx: packed::Transaction = self
+                            .wallet
+                            .query_transaction(&input_cell.tx_hash)?
+                            .expect("tx")
+                            .transaction
+                            .inner
+                            .into();
+                        let tx: TransactionView = tx.into_view();
+                        let input_cell_output =
+                            tx.outputs().get(input_cell.index as usize).expect("output");
+                        // inherit type id from input cell or create a new one
+                        match input_cell_output.type_().to_opt() {
+                            Some(script) if is_type_id_script(&script) => script,
+                            _ => build_type_id_script(&first_input, output_index as u64),
+                        }
                     }
+                    None => build_type_id_script(&first_input, output_index as u64),
                 };
                 output = output.type_(Some(type_script).pack());
             }
-            output
+            let output = output
                 .build_exact_capacity(Capacity::bytes(data.len()).expect("bytes"))
-                .expect("build")
-        };
+                .expect("build");
+            outputs.push(output);
+            outputs_data.push(data.pack());
+        }
+
         let tx = TransactionBuilder::default()
             .inputs(inputs_cells.iter().map(|cell| cell.input()))
-            .output(output)
-            .output_data(data.pack())
+            .outputs(outputs)
+            .outputs_data(outputs_data)
             .build();
         let tx = self.wallet.complete_tx_lock_deps(tx);
         let inputs_capacity = inputs_cells.iter().map(|cell| cell.capacity).sum::<u64>();
@@ -128,7 +458,13 @@ impl DeploymentProcess {
             self.wallet
                 .complete_tx_inputs(tx, Capacity::shannons(inputs_capacity), self.tx_fee);
         self.wallet.lock_tx_inputs(&tx);
-        Ok(tx)
+
+        let cell_recipes = batch
+            .into_iter()
+            .enumerate()
+            .map(|(index, (cell, _data))| build_cell_recipe(&tx, index as u32, cell))
+            .collect();
+        Ok((tx, cell_recipes))
     }
 
     fn build_dep_group_tx(
@@ -206,31 +542,46 @@ impl DeploymentProcess {
         let mut txs = Vec::new();
         let mut cell_recipes = Vec::new();
         let mut dep_group_recipes = Vec::new();
-        // build cells tx
+        // greedily pack cells into as few transactions as possible: keep
+        // adding cells to the current batch until the next one would push
+        // the batch's serialized outputs over `max_tx_size`, then flush and
+        // start a new one
+        let mut batch: Vec<(Cell, Bytes)> = Vec::new();
+        let mut batch_outputs: Vec<packed::CellOutput> = Vec::new();
+        let mut batch_outputs_data: Vec<Bytes> = Vec::new();
+        let lock: packed::Script = self.config.lock.to_owned().into();
         for (cell, data) in cells {
-            let mut input_cells = Vec::new();
-            if let Some(input_cell) = pre_inputs_cells
-                .iter()
-                .find(|(name, _cell)| name == &cell.name)
-                .map(|(_name, input_cell)| input_cell.clone())
-            {
-                input_cells.push(input_cell);
+            // the real type-id script's args are a 32-byte hash derived from the
+            // batch's eventual input, but every type-id script has the same
+            // serialized length regardless of the hash's value, so a
+            // placeholder of that shape is enough to estimate the tx's size
+            let mut output = packed::CellOutput::new_builder().lock(lock.clone());
+            if cell.enable_type_id {
+                output = output.type_(Some(placeholder_type_id_script()).pack());
             }
-            // search change cells from previous tx
-            if let Some(tx) = txs.last() {
-                let change_outputs = self.search_changes(tx);
-                trace!(
-                    "found change outputs from previous tx: {:?}",
-                    change_outputs
-                );
-                input_cells.extend(change_outputs);
+            let output = output
+                .build_exact_capacity(Capacity::bytes(data.len()).expect("bytes"))
+                .expect("build");
+
+            if !batch.is_empty()
+                && exceeds_size_budget(
+                    &batch_outputs,
+                    &batch_outputs_data,
+                    &output,
+                    &data,
+                    self.max_tx_size,
+                )
+            {
+                self.flush_cell_batch(&mut batch, &pre_inputs_cells, &mut txs, &mut cell_recipes)?;
+                batch_outputs.clear();
+                batch_outputs_data.clear();
             }
-            let tx = self
-                .build_cell_tx(cell.clone(), data, input_cells)
-                .expect("cell deployment tx");
-            let cell_recipe = build_cell_recipe(&tx, cell);
-            txs.push(tx);
-            cell_recipes.push(cell_recipe);
+            batch_outputs.push(output);
+            batch_outputs_data.push(data.clone());
+            batch.push((cell, data));
+        }
+        if !batch.is_empty() {
+            self.flush_cell_batch(&mut batch, &pre_inputs_cells, &mut txs, &mut cell_recipes)?;
         }
         // build dep_groups tx
         for dep_group in dep_groups {
@@ -264,6 +615,41 @@ impl DeploymentProcess {
         Ok((recipe, txs))
     }
 
+    // build a tx for the accumulated batch, taking the designated pre-input
+    // of each cell in the batch plus any change left over from the previous
+    // tx as inputs, then append the resulting tx/recipes and reset `batch`
+    fn flush_cell_batch(
+        &mut self,
+        batch: &mut Vec<(Cell, Bytes)>,
+        pre_inputs_cells: &[(String, LiveCell)],
+        txs: &mut Vec<TransactionView>,
+        cell_recipes: &mut Vec<CellRecipe>,
+    ) -> Result<()> {
+        let mut input_cells = Vec::new();
+        for (cell, _data) in batch.iter() {
+            if let Some(input_cell) = pre_inputs_cells
+                .iter()
+                .find(|(name, _cell)| name == &cell.name)
+                .map(|(_name, input_cell)| input_cell.clone())
+            {
+                input_cells.push(input_cell);
+            }
+        }
+        if let Some(tx) = txs.last() {
+            let change_outputs = self.search_changes(tx);
+            trace!(
+                "found change outputs from previous tx: {:?}",
+                change_outputs
+            );
+            input_cells.extend(change_outputs);
+        }
+        let batch = std::mem::take(batch);
+        let (tx, recipes) = self.build_cell_batch_tx(batch, pre_inputs_cells, input_cells)?;
+        txs.push(tx);
+        cell_recipes.extend(recipes);
+        Ok(())
+    }
+
     // search change outputs from a tx
     fn search_changes(&self, tx: &TransactionView) -> Vec<LiveCell> {
         let tx_hash = tx.hash();
@@ -284,88 +670,199 @@ impl DeploymentProcess {
     }
 
     pub fn sign_txs(&self, txs: Vec<TransactionView>) -> Result<Vec<TransactionView>> {
-        let password = self.wallet.read_password().expect("read password");
         txs.into_iter()
-            .map(|tx| self.wallet.sign_tx(tx, password.clone()))
+            .map(|tx| {
+                let context = self.build_signing_context(&tx);
+                self.signer.sign(tx, context)
+            })
+            .collect()
+    }
+
+    // like `sign_txs`, but takes each tx's input locks instead of deriving
+    // them via `self.wallet`, so an offline/network-less signer can use it
+    pub fn sign_txs_with_locks(
+        &self,
+        txs: Vec<TransactionView>,
+        input_locks: Vec<Vec<packed::Script>>,
+    ) -> Result<Vec<TransactionView>> {
+        txs.into_iter()
+            .zip(input_locks)
+            .map(|(tx, input_locks)| {
+                let context = SigningContext { input_locks };
+                self.signer.sign(tx, context)
+            })
             .collect()
     }
 
+    fn build_signing_context(&self, tx: &TransactionView) -> SigningContext {
+        let input_locks = tx
+            .inputs()
+            .into_iter()
+            .map(|input| self.wallet.get_cell_output(input.previous_output()).lock())
+            .collect();
+        SigningContext { input_locks }
+    }
+
+    // send every tx in `recipe`, in order, waiting for each cell tx to reach
+    // `confirmation_depth` before sending the dep-group txs that reference it;
+    // a tx already sent/confirmed in a previous or earlier-in-this-run step is
+    // detected and not resent
     pub fn execute_recipe(
         &mut self,
         recipe: DeploymentRecipe,
         txs: Vec<TransactionView>,
     ) -> Result<()> {
-        let mut i = 0;
-        for cell_recipe in recipe.cell_recipes {
-            println!("{:x?}", cell_recipe.tx_hash);
-            // Looks up all cell tx hashes...
-            // Why though???
-            // To make sure it wasn't already transacted perhaps?
-            // TODO: Figure out what this code snippet is for
-            // if self
-            //     .wallet
-            //     .query_transaction(&cell_recipe.tx_hash)?
-            //     .is_some()
-            // {
-            //     continue;
-            // }
-            // So if the cell is not included, we should simply just skip...
-            let tx = txs
-                .iter()
-                .find(|tx| {
-                    let tx_hash = tx.hash().unpack();
-                    cell_recipe.tx_hash == tx_hash
-                })
-                .expect("missing recipe tx");
-            let tx_hash: H256 = tx.hash().unpack();
-            i += 1;
-            println!("({}/{}) Sending tx {}", i, txs.len(), tx_hash);
-
-            if log_enabled!(Debug) {
-                let tx_without_data = tx
-                    .as_advanced_builder()
-                    .set_outputs_data(Vec::new())
-                    .build();
-                debug!("send transaction error: {}", tx_without_data);
-            }
+        let total_steps = recipe.cell_recipes.len() + recipe.dep_group_recipes.len();
+        let mut progress = self
+            .progress_path
+            .clone()
+            .map(|path| DeploymentProgress::load(&path))
+            .unwrap_or_default();
+        let mut confirmed_this_run: HashSet<H256> = HashSet::new();
+        let mut step = 0;
+        for cell_recipe in &recipe.cell_recipes {
+            step += 1;
+            self.send_and_confirm_step(
+                &cell_recipe.name,
+                &cell_recipe.tx_hash,
+                &txs,
+                step,
+                total_steps,
+                &mut progress,
+                &mut confirmed_this_run,
+            )?;
+        }
+        // dep-group txs reference cell out-points directly, so every cell tx
+        // must be confirmed before the dep-groups that depend on it are sent
+        for dep_group_recipe in &recipe.dep_group_recipes {
+            step += 1;
+            self.send_and_confirm_step(
+                &dep_group_recipe.name,
+                &dep_group_recipe.tx_hash,
+                &txs,
+                step,
+                total_steps,
+                &mut progress,
+                &mut confirmed_this_run,
+            )?;
+        }
+        Ok(())
+    }
 
-            self.wallet.send_transaction(tx.to_owned())?;
+    #[allow(clippy::too_many_arguments)]
+    fn send_and_confirm_step(
+        &mut self,
+        name: &str,
+        tx_hash: &H256,
+        txs: &[TransactionView],
+        step: usize,
+        total_steps: usize,
+        progress: &mut DeploymentProgress,
+        confirmed_this_run: &mut HashSet<H256>,
+    ) -> Result<()> {
+        if already_confirmed_from_previous_run(progress, name, tx_hash) {
+            println!(
+                "({}/{}) {} already confirmed, skipping",
+                step, total_steps, name
+            );
+            return Ok(());
         }
-        for dep_group_recipe in recipe.dep_group_recipes {
-            if self
-                .wallet
-                .query_transaction(&dep_group_recipe.tx_hash)?
-                .is_some()
-            {
-                continue;
-            }
-            let tx = txs
-                .iter()
-                .find(|tx| {
-                    let tx_hash = tx.hash().unpack();
-                    dep_group_recipe.tx_hash == tx_hash
-                })
-                .expect("missing recipe tx");
-            let tx_hash: H256 = tx.hash().unpack();
-            i += 1;
-            println!("({}/{}) Sending tx {}", i, txs.len(), tx_hash);
-
-            if log_enabled!(Debug) {
-                let tx_without_data = tx
-                    .as_advanced_builder()
-                    .set_outputs_data(Vec::new())
-                    .build();
-                debug!("send transaction error: {}", tx_without_data);
+        // a batched tx produces one recipe step per cell/dep-group it carries;
+        // once one step has confirmed tx_hash this run, the rest can skip the poll
+        if confirmed_this_run.contains(tx_hash) {
+            println!(
+                "({}/{}) {} shares already-confirmed tx {}, skipping",
+                step, total_steps, name, tx_hash
+            );
+        } else {
+            if self.wallet.query_transaction(tx_hash)?.is_some() {
+                println!(
+                    "({}/{}) {} already sent as {}, waiting for confirmation",
+                    step, total_steps, name, tx_hash
+                );
+            } else {
+                let tx = txs
+                    .iter()
+                    .find(|tx| {
+                        let candidate: H256 = tx.hash().unpack();
+                        &candidate == tx_hash
+                    })
+                    .expect("missing recipe tx");
+                println!("({}/{}) Sending tx {}", step, total_steps, tx_hash);
+                if log_enabled!(Debug) {
+                    let tx_without_data = tx
+                        .as_advanced_builder()
+                        .set_outputs_data(Vec::new())
+                        .build();
+                    debug!("send transaction error: {}", tx_without_data);
+                }
+                self.wallet.send_transaction(tx.to_owned())?;
             }
-
-            self.wallet.send_transaction(tx.to_owned())?;
+            self.wait_for_confirmation(tx_hash)?;
+            confirmed_this_run.insert(tx_hash.to_owned());
+        }
+        progress
+            .confirmed
+            .insert(name.to_owned(), tx_hash.to_owned());
+        if let Some(path) = &self.progress_path {
+            progress.save(path)?;
         }
         Ok(())
     }
+
+    // poll until `tx_hash` has been committed for at least `confirmation_depth`
+    // further blocks, giving up after `MAX_CONFIRMATION_ATTEMPTS` seconds so a
+    // dropped/replaced tx can't hang `execute_recipe` forever
+    fn wait_for_confirmation(&self, tx_hash: &H256) -> Result<()> {
+        const MAX_CONFIRMATION_ATTEMPTS: u32 = 300;
+        for _ in 0..MAX_CONFIRMATION_ATTEMPTS {
+            if let Some(status) = self.wallet.query_transaction(tx_hash)? {
+                if let Some(committed_at) = status.tx_status.block_number {
+                    let tip = self.wallet.get_tip_block_number()?;
+                    if tip.saturating_sub(committed_at.into()) >= self.confirmation_depth {
+                        return Ok(());
+                    }
+                }
+            }
+            sleep(Duration::from_secs(1));
+        }
+        Err(anyhow!(
+            "timed out after {} attempts waiting for tx {} to reach {} confirmations",
+            MAX_CONFIRMATION_ATTEMPTS,
+            tx_hash,
+            self.confirmation_depth
+        ))
+    }
 }
 
-fn build_cell_recipe(tx: &TransactionView, cell: Cell) -> CellRecipe {
-    let index = 0;
+// estimate the serialized size of a tx carrying only these outputs, used to
+// decide when a batch has grown too large and should be split
+fn estimate_outputs_size(outputs: &[packed::CellOutput], outputs_data: &[Bytes]) -> usize {
+    let tx = TransactionBuilder::default()
+        .outputs(outputs.iter().cloned())
+        .outputs_data(outputs_data.iter().map(|data| data.pack()))
+        .build();
+    tx.data().as_slice().len()
+}
+
+// whether adding `candidate_output`/`candidate_data` to a batch already
+// holding `batch_outputs`/`batch_outputs_data` would push it over `max_tx_size`
+fn exceeds_size_budget(
+    batch_outputs: &[packed::CellOutput],
+    batch_outputs_data: &[Bytes],
+    candidate_output: &packed::CellOutput,
+    candidate_data: &Bytes,
+    max_tx_size: usize,
+) -> bool {
+    let mut trial_outputs = batch_outputs.to_vec();
+    trial_outputs.push(candidate_output.clone());
+    let mut trial_outputs_data = batch_outputs_data.to_vec();
+    trial_outputs_data.push(candidate_data.clone());
+    estimate_outputs_size(&trial_outputs, &trial_outputs_data) > max_tx_size
+}
+
+fn build_cell_recipe(tx: &TransactionView, index: u32, cell: Cell) -> CellRecipe {
+    let index = index as usize;
     let cell_output = tx.outputs().get(index).expect("get cell");
     let data: Bytes = tx.outputs_data().get(index).expect("get data").unpack();
     let occupied_capacity = cell_output
@@ -391,6 +888,7 @@ fn build_cell_recipe(tx: &TransactionView, cell: Cell) -> CellRecipe {
         occupied_capacity,
         tx_hash: tx.hash().unpack(),
         type_id,
+        hash_type: cell.hash_type,
     }
 }
 
@@ -409,9 +907,15 @@ fn build_dep_group_recipe(tx: &TransactionView, dep_group: DepGroup) -> DepGroup
         name: dep_group.name.to_owned(),
         occupied_capacity,
         tx_hash: tx.hash().unpack(),
+        hash_type: dep_group.hash_type,
     }
 }
 
+// `script` is a type-id script iff it uses the reserved type-id code hash
+// under `ScriptHashType::Type`; a cell whose own `hash_type` is `Data`/`Data1`
+// still needs this check to key off `Type` specifically, since the type-id
+// system script is defined in terms of it regardless of how the *deploying*
+// cell is referenced downstream
 fn is_type_id_script(script: &packed::Script) -> bool {
     script.code_hash() == TYPE_ID_CODE_HASH.pack()
         && script.hash_type() == ScriptHashType::Type.into()
@@ -431,6 +935,18 @@ fn build_type_id_script(input: &packed::CellInput, output_index: u64) -> packed:
         .build()
 }
 
+// same shape as `build_type_id_script`'s output but with a zeroed arg, for
+// estimating a not-yet-built batch's serialized size before its real input
+// is known; every type-id script's args is a 32-byte hash, so this is the
+// same length as whatever `build_type_id_script` will actually produce
+fn placeholder_type_id_script() -> packed::Script {
+    packed::Script::new_builder()
+        .code_hash(TYPE_ID_CODE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(vec![0u8; 32]).pack())
+        .build()
+}
+
 fn load_deployable_cells_data(cells: &[Cell]) -> Result<Vec<(Cell, Bytes)>> {
     let mut cells_data: Vec<(Cell, Bytes)> = Vec::new();
     for cell in cells {
@@ -460,3 +976,191 @@ fn load_deployable_cells_data(cells: &[Cell]) -> Result<Vec<(Cell, Bytes)>> {
     }
     Ok(cells_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_lock_json_round_trips_through_export_import_conversion() {
+        // exercises the same `packed::Script` <-> `JsonScript` conversion
+        // `export_unsigned_recipe`/`import_unsigned_recipe` use to carry
+        // per-input locks through the bundle; a full `UnsignedDeploymentBundle`
+        // round trip also needs a `DeploymentRecipe`, whose definition lives
+        // outside this file
+        let lock = packed::Script::new_builder()
+            .code_hash(TYPE_ID_CODE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(vec![9u8; 32]).pack())
+            .build();
+        let json: JsonScript = lock.clone().into();
+        let round_tripped: packed::Script = json.into();
+        assert_eq!(round_tripped, lock);
+    }
+
+    #[test]
+    fn deployment_progress_save_load_round_trips() {
+        let path = std::env::temp_dir().join("capsule_test_deployment_progress_round_trip.json");
+        let path = path.to_str().expect("utf8 path");
+        let mut progress = DeploymentProgress::default();
+        progress
+            .confirmed
+            .insert("my-cell".to_owned(), H256::default());
+        progress.save(path).unwrap();
+
+        let loaded = DeploymentProgress::load(path);
+        assert_eq!(loaded.confirmed, progress.confirmed);
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn deployment_progress_load_defaults_when_file_is_missing() {
+        let loaded = DeploymentProgress::load("/nonexistent/capsule_test_progress.json");
+        assert!(loaded.confirmed.is_empty());
+    }
+
+    #[test]
+    fn already_confirmed_from_previous_run_matches_recorded_tx_hash() {
+        let mut progress = DeploymentProgress::default();
+        let confirmed_hash = H256::default();
+        progress
+            .confirmed
+            .insert("my-cell".to_owned(), confirmed_hash.clone());
+
+        assert!(already_confirmed_from_previous_run(
+            &progress,
+            "my-cell",
+            &confirmed_hash
+        ));
+        // a different name, or the same name with a stale tx_hash (recipe
+        // rebuilt with different inputs), must not be treated as confirmed
+        assert!(!already_confirmed_from_previous_run(
+            &progress,
+            "other-cell",
+            &confirmed_hash
+        ));
+        let stale_hash: H256 = H256::from([1u8; 32]);
+        assert!(!already_confirmed_from_previous_run(
+            &progress,
+            "my-cell",
+            &stale_hash
+        ));
+    }
+
+    fn dummy_output(data_len: usize) -> (packed::CellOutput, Bytes) {
+        let data = Bytes::from(vec![0u8; data_len]);
+        let output = packed::CellOutput::new_builder()
+            .build_exact_capacity(Capacity::bytes(data.len()).expect("bytes"))
+            .expect("build");
+        (output, data)
+    }
+
+    #[test]
+    fn exceeds_size_budget_stays_under_threshold_for_small_batch() {
+        let (output, data) = dummy_output(8);
+        let budget = estimate_outputs_size(&[output.clone()], &[data.clone()]) + 1;
+        assert!(!exceeds_size_budget(&[], &[], &output, &data, budget));
+    }
+
+    #[test]
+    fn exceeds_size_budget_forces_a_split_once_a_cell_no_longer_fits() {
+        let (first_output, first_data) = dummy_output(8);
+        // a budget sized for exactly one cell forces the second into a new batch
+        let budget = estimate_outputs_size(&[first_output.clone()], &[first_data.clone()]);
+        let (second_output, second_data) = dummy_output(8);
+        assert!(exceeds_size_budget(
+            &[first_output],
+            &[first_data],
+            &second_output,
+            &second_data,
+            budget
+        ));
+    }
+
+    fn build_tx_with_witnesses(count: usize) -> TransactionView {
+        let witnesses = (0..count)
+            .map(|i| Bytes::from(vec![i as u8; 4]).pack())
+            .collect::<Vec<_>>();
+        TransactionBuilder::default().witnesses(witnesses).build()
+    }
+
+    #[test]
+    fn apply_signature_preserves_other_witnesses() {
+        let tx = build_tx_with_witnesses(3);
+        let original_witnesses: Vec<_> = tx.witnesses().into_iter().collect();
+        let context = SigningContext {
+            input_locks: vec![],
+        };
+
+        let signed = apply_signature(tx, &context, [7u8; 65]);
+        let signed_witnesses: Vec<_> = signed.witnesses().into_iter().collect();
+
+        assert_eq!(signed_witnesses.len(), 3);
+        assert_eq!(signed_witnesses[1], original_witnesses[1]);
+        assert_eq!(signed_witnesses[2], original_witnesses[2]);
+        let witness_args =
+            packed::WitnessArgs::from_slice(&signed_witnesses[0].raw_data()).unwrap();
+        assert_eq!(
+            witness_args.lock().to_opt().unwrap().raw_data().as_ref(),
+            [7u8; 65]
+        );
+    }
+
+    fn dummy_lock(arg: u8) -> packed::Script {
+        packed::Script::new_builder()
+            .args(Bytes::from(vec![arg]).pack())
+            .build()
+    }
+
+    #[test]
+    fn single_lock_group_accepts_uniform_locks() {
+        let context = SigningContext {
+            input_locks: vec![dummy_lock(1), dummy_lock(1)],
+        };
+        assert_eq!(context.single_lock_group().unwrap(), &dummy_lock(1));
+    }
+
+    #[test]
+    fn single_lock_group_rejects_mixed_locks() {
+        let context = SigningContext {
+            input_locks: vec![dummy_lock(1), dummy_lock(2)],
+        };
+        assert!(context.single_lock_group().is_err());
+    }
+
+    #[test]
+    fn signing_message_hashes_all_witnesses() {
+        let tx = build_tx_with_witnesses(2);
+        let message = signing_message(&tx);
+
+        let mut blake2b = new_blake2b();
+        blake2b.update(tx.hash().as_slice());
+        for witness in tx.witnesses() {
+            let raw = witness.raw_data();
+            blake2b.update(&(raw.len() as u64).to_le_bytes());
+            blake2b.update(&raw);
+        }
+        let mut expected = [0u8; 32];
+        blake2b.finalize(&mut expected);
+
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn is_type_id_script_keys_off_type_regardless_of_cells_own_hash_type() {
+        // the type-id system script is always referenced with
+        // ScriptHashType::Type; a deployed cell's own `hash_type` (Data/Data1,
+        // used by downstream scripts to reference *this* cell's code) is a
+        // separate concern and must not affect type-id detection on redeploy
+        let input = packed::CellInput::new_builder().build();
+        let type_id_script = build_type_id_script(&input, 0);
+        assert!(is_type_id_script(&type_id_script));
+
+        let data1_script = packed::Script::new_builder()
+            .code_hash(TYPE_ID_CODE_HASH.pack())
+            .hash_type(ScriptHashType::Data1.into())
+            .build();
+        assert!(!is_type_id_script(&data1_script));
+    }
+}